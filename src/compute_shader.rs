@@ -0,0 +1,592 @@
+//! Generic infrastructure for driving a multi-pass GPU compute graph from the render graph.
+//!
+//! Implement [`ComputeGraph`] for a marker type describing your passes: each [`ComputePass`]
+//! declares the entry point it runs and the named buffer [`Slot`]s it reads and writes.
+//! `ComputeGraphPlugin` resolves those slots to the right `GpuShaderStorageBuffer` bindings and
+//! topologically sorts the passes by their slot dependencies (a pass that reads a slot another
+//! pass writes always dispatches after it, regardless of declaration order), running them in
+//! that order inside one command encoder, every frame — so passes can chain, e.g. `build_grid`
+//! -> `compute_forces` -> `integrate`, sharing intermediate GPU buffers that a single fixed bind
+//! group couldn't express.
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::marker::PhantomData;
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph, RenderLabel},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        storage::{GpuShaderStorageBuffer, ShaderStorageBuffer},
+        Render, RenderApp, RenderSet,
+    },
+};
+
+pub type SlotId = &'static str;
+
+/// A buffer a [`ComputePass`] binds. Storage slots are resolved to a real buffer every frame
+/// via [`ComputeGraph::resolve_storage_slot`]; [`Slot::Uniform`] always binds
+/// [`ComputeGraph::uniform_buffer`].
+pub enum Slot {
+    Storage { name: SlotId, read_only: bool },
+    Uniform,
+}
+
+/// One binding within a pass's bind group.
+pub struct SlotBinding {
+    pub binding: u32,
+    pub slot: Slot,
+}
+
+/// A single dispatch within a [`ComputeGraph`]: its entry point and the slots it binds.
+pub struct ComputePass {
+    pub entry_point: &'static str,
+    pub bindings: &'static [SlotBinding],
+    /// If true, this pass dispatches at most once — after it first dispatches successfully,
+    /// [`ComputeGraphNode`] skips it on every later frame. For one-time GPU-side setup (e.g. an
+    /// `init` entry point) as opposed to the default steady-state passes that redispatch every
+    /// frame.
+    pub run_once: bool,
+}
+
+/// Describes a multi-pass GPU compute graph that [`ComputeGraphPlugin`] can drive.
+///
+/// `PreparedData` is whatever the main world needs to hand the render world each frame: buffer
+/// handles, counts, and anything else `resolve_storage_slot`/`uniform_buffer` need. It is
+/// extracted automatically.
+pub trait ComputeGraph: Send + Sync + Sized + 'static {
+    type PreparedData: Resource + Clone + ExtractResource;
+
+    /// Used in pipeline/bind-group-layout debug labels.
+    const LABEL: &'static str;
+
+    /// Path to the WGSL shader, relative to the `assets` folder.
+    fn shader_path() -> &'static str;
+
+    /// The graph's passes. Declaration order only matters as a tie-break between passes with no
+    /// dependency between them — [`ComputeGraphPipeline`] topologically sorts them by their
+    /// storage slot reads/writes before ever dispatching one, so a pass reading a slot another
+    /// pass writes always runs after it. A slot nothing in the graph writes (e.g. one seeded
+    /// from the main world, like the particle sim's initial state) is treated as external input
+    /// and imposes no ordering constraint.
+    fn passes() -> &'static [ComputePass];
+
+    /// Resolve a named storage slot to the buffer asset backing it this frame.
+    fn resolve_storage_slot(
+        data: &Self::PreparedData,
+        slot: SlotId,
+    ) -> AssetId<ShaderStorageBuffer>;
+
+    /// The buffer bound wherever a pass declares a [`Slot::Uniform`].
+    fn uniform_buffer(data: &Self::PreparedData) -> &Buffer;
+
+    /// Workgroup dispatch dimensions for the pass at `pass_index` in [`ComputeGraph::passes`]
+    /// (its declared index, not its position in the topologically-sorted dispatch order).
+    fn workgroup_count(data: &Self::PreparedData, pass_index: usize) -> UVec3;
+
+    /// Write any per-frame uniform data (elapsed time, ...) into GPU buffers. Runs every frame,
+    /// before the bind groups are rebuilt. Default no-op for graphs with nothing time-varying.
+    fn write_uniforms(_data: &Self::PreparedData, _queue: &RenderQueue, _time: &Time) {}
+}
+
+/// Adds a [`ComputeGraph`] implementation to the render graph as a node feeding into the camera
+/// driver.
+pub struct ComputeGraphPlugin<G>(PhantomData<G>);
+
+impl<G> Default for ComputeGraphPlugin<G> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+struct ComputeLabel<G: Send + Sync + 'static>(PhantomData<G>);
+
+impl<G: Send + Sync + 'static> Default for ComputeLabel<G> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<G: ComputeGraph> Plugin for ComputeGraphPlugin<G> {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(ExtractResourcePlugin::<G::PreparedData>::default());
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.add_systems(
+            Render,
+            (
+                write_uniforms::<G>.in_set(RenderSet::Prepare),
+                prepare_bind_groups::<G>
+                    .in_set(RenderSet::Prepare)
+                    .after(write_uniforms::<G>),
+            ),
+        );
+
+        let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
+        render_graph.add_node(
+            ComputeLabel::<G>::default(),
+            ComputeGraphNode::<G>::default(),
+        );
+        render_graph.add_node_edge(
+            ComputeLabel::<G>::default(),
+            bevy::render::graph::CameraDriverLabel,
+        );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.init_resource::<ComputeGraphPipeline<G>>();
+    }
+}
+
+fn write_uniforms<G: ComputeGraph>(
+    data: Res<G::PreparedData>,
+    queue: Res<RenderQueue>,
+    time: Res<Time>,
+) {
+    G::write_uniforms(&data, &queue, &time);
+}
+
+#[derive(Resource)]
+struct ComputeBindGroups<G>(Vec<BindGroup>, PhantomData<G>);
+
+// Unlike the old single-bind-group pipeline, slots can resolve to a different buffer from one
+// frame to the next (that's the whole point - it's how ping-pong buffering is expressed now),
+// so these are rebuilt every frame rather than once.
+fn prepare_bind_groups<G: ComputeGraph>(
+    mut commands: Commands,
+    pipeline: Res<ComputeGraphPipeline<G>>,
+    data: Res<G::PreparedData>,
+    render_device: Res<RenderDevice>,
+    storage_buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+) {
+    let mut bind_groups = Vec::with_capacity(pipeline.passes.len());
+    for (&pass_index, pass_pipeline) in pipeline.order.iter().zip(&pipeline.passes) {
+        let pass = &G::passes()[pass_index];
+        let mut entries = Vec::with_capacity(pass.bindings.len());
+        for binding in pass.bindings {
+            let resource = match &binding.slot {
+                Slot::Storage { name, .. } => {
+                    let Some(buffer) = storage_buffers.get(G::resolve_storage_slot(&data, name))
+                    else {
+                        // Buffer asset not uploaded to the GPU yet (or no longer valid). Drop
+                        // any bind groups built last frame rather than leaving them in place:
+                        // the node would otherwise keep dispatching against stale buffers
+                        // instead of skipping the frame.
+                        commands.remove_resource::<ComputeBindGroups<G>>();
+                        return;
+                    };
+                    buffer.buffer.as_entire_binding()
+                }
+                Slot::Uniform => G::uniform_buffer(&data).as_entire_binding(),
+            };
+            entries.push(BindGroupEntry {
+                binding: binding.binding,
+                resource,
+            });
+        }
+        bind_groups.push(render_device.create_bind_group(
+            G::LABEL,
+            &pass_pipeline.bind_group_layout,
+            &entries,
+        ));
+    }
+    commands.insert_resource(ComputeBindGroups::<G>(bind_groups, PhantomData));
+}
+
+/// Blocks the calling thread until every pass of `G`'s pipeline has finished compiling (or
+/// failed), for callers who'd rather pay that cost up front than skip a few frames of dispatch
+/// while it compiles in the background. Not called by [`ComputeGraphPlugin`] itself.
+pub fn block_until_compiled<G: ComputeGraph>(world: &mut World) {
+    loop {
+        world.resource_mut::<PipelineCache>().process_queue();
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline = world.resource::<ComputeGraphPipeline<G>>();
+        let still_compiling = pipeline.passes.iter().any(|pass| {
+            matches!(
+                pipeline_cache.get_compute_pipeline_state(pass.pipeline_id),
+                CachedPipelineState::Queued | CachedPipelineState::Creating(_)
+            )
+        });
+        if !still_compiling {
+            return;
+        }
+    }
+}
+
+/// Topologically sorts `G::passes()` by storage slot dependency. See [`topological_order_of`].
+fn topological_order<G: ComputeGraph>() -> Vec<usize> {
+    topological_order_of(G::passes(), G::LABEL)
+}
+
+/// Topologically sorts `passes` by storage slot dependency: if pass `b` reads a slot pass `a`
+/// writes, `a`'s index precedes `b`'s in the returned order. Passes with no dependency between
+/// them keep their relative declaration order. Panics if `passes` forms a slot dependency cycle,
+/// since that can never be scheduled into a linear dispatch order. `label` is only used in that
+/// panic message.
+fn topological_order_of(passes: &[ComputePass], label: &str) -> Vec<usize> {
+    let mut writers_of: std::collections::HashMap<SlotId, Vec<usize>> =
+        std::collections::HashMap::new();
+    for (index, pass) in passes.iter().enumerate() {
+        for binding in pass.bindings {
+            if let Slot::Storage {
+                name,
+                read_only: false,
+            } = binding.slot
+            {
+                writers_of.entry(name).or_default().push(index);
+            }
+        }
+    }
+
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); passes.len()];
+    let mut remaining_deps = vec![0usize; passes.len()];
+    for (index, pass) in passes.iter().enumerate() {
+        for binding in pass.bindings {
+            if let Slot::Storage {
+                name,
+                read_only: true,
+            } = binding.slot
+            {
+                if let Some(writers) = writers_of.get(name) {
+                    for &writer in writers {
+                        if writer != index {
+                            dependents[writer].push(index);
+                            remaining_deps[index] += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Kahn's algorithm; a min-heap over pending indices keeps dependency-free passes in their
+    // original declaration order.
+    let mut ready: std::collections::BinaryHeap<std::cmp::Reverse<usize>> = (0..passes.len())
+        .filter(|&index| remaining_deps[index] == 0)
+        .map(std::cmp::Reverse)
+        .collect();
+
+    let mut order = Vec::with_capacity(passes.len());
+    while let Some(std::cmp::Reverse(index)) = ready.pop() {
+        order.push(index);
+        for &dependent in &dependents[index] {
+            remaining_deps[dependent] -= 1;
+            if remaining_deps[dependent] == 0 {
+                ready.push(std::cmp::Reverse(dependent));
+            }
+        }
+    }
+
+    assert_eq!(
+        order.len(),
+        passes.len(),
+        "{label}: compute graph has a cyclic slot dependency between passes"
+    );
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage_binding(binding: u32, name: SlotId, read_only: bool) -> SlotBinding {
+        SlotBinding {
+            binding,
+            slot: Slot::Storage { name, read_only },
+        }
+    }
+
+    #[test]
+    fn single_pass_runs_alone() {
+        let passes = [ComputePass {
+            entry_point: "only",
+            bindings: &[],
+            run_once: false,
+        }];
+        assert_eq!(topological_order_of(&passes, "test"), vec![0]);
+    }
+
+    #[test]
+    fn independent_passes_keep_declaration_order() {
+        let reads_a = [storage_binding(0, "a", true)];
+        let reads_b = [storage_binding(0, "b", true)];
+        let passes = [
+            ComputePass {
+                entry_point: "first",
+                bindings: &reads_a,
+                run_once: false,
+            },
+            ComputePass {
+                entry_point: "second",
+                bindings: &reads_b,
+                run_once: false,
+            },
+        ];
+        assert_eq!(topological_order_of(&passes, "test"), vec![0, 1]);
+    }
+
+    #[test]
+    fn reorders_a_two_pass_chain_by_slot_dependency() {
+        // Declared consumer-before-producer; the real dependency should still put the writer
+        // ("producer", index 1) ahead of the reader ("consumer", index 0).
+        let consumes = [storage_binding(0, "x", true)];
+        let produces = [storage_binding(0, "x", false)];
+        let passes = [
+            ComputePass {
+                entry_point: "consumer",
+                bindings: &consumes,
+                run_once: false,
+            },
+            ComputePass {
+                entry_point: "producer",
+                bindings: &produces,
+                run_once: false,
+            },
+        ];
+        assert_eq!(topological_order_of(&passes, "test"), vec![1, 0]);
+    }
+
+    #[test]
+    fn reorders_a_three_pass_chain_by_slot_dependency() {
+        // Declared out of order: integrate (reads forces, writes positions), build_grid (writes
+        // grid), compute_forces (reads grid, writes forces). Correct run order is
+        // build_grid -> compute_forces -> integrate, i.e. indices [1, 2, 0].
+        let integrate_bindings = [
+            storage_binding(0, "forces", true),
+            storage_binding(1, "positions", false),
+        ];
+        let build_grid_bindings = [storage_binding(0, "grid", false)];
+        let compute_forces_bindings = [
+            storage_binding(0, "grid", true),
+            storage_binding(1, "forces", false),
+        ];
+        let passes = [
+            ComputePass {
+                entry_point: "integrate",
+                bindings: &integrate_bindings,
+                run_once: false,
+            },
+            ComputePass {
+                entry_point: "build_grid",
+                bindings: &build_grid_bindings,
+                run_once: false,
+            },
+            ComputePass {
+                entry_point: "compute_forces",
+                bindings: &compute_forces_bindings,
+                run_once: false,
+            },
+        ];
+        assert_eq!(topological_order_of(&passes, "test"), vec![1, 2, 0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cyclic slot dependency")]
+    fn panics_on_a_slot_dependency_cycle() {
+        // a reads "y" and writes "x"; b reads "x" and writes "y" - each depends on the other.
+        let a_bindings = [
+            storage_binding(0, "y", true),
+            storage_binding(1, "x", false),
+        ];
+        let b_bindings = [
+            storage_binding(0, "x", true),
+            storage_binding(1, "y", false),
+        ];
+        let passes = [
+            ComputePass {
+                entry_point: "a",
+                bindings: &a_bindings,
+                run_once: false,
+            },
+            ComputePass {
+                entry_point: "b",
+                bindings: &b_bindings,
+                run_once: false,
+            },
+        ];
+        topological_order_of(&passes, "test");
+    }
+}
+
+struct PassPipeline {
+    bind_group_layout: BindGroupLayout,
+    pipeline_id: CachedComputePipelineId,
+}
+
+#[derive(Resource)]
+struct ComputeGraphPipeline<G> {
+    /// Indices into `G::passes()`, in dispatch order.
+    order: Vec<usize>,
+    /// Parallel to `order`: `passes[i]` is built from `G::passes()[order[i]]`.
+    passes: Vec<PassPipeline>,
+    _marker: PhantomData<G>,
+}
+
+impl<G: ComputeGraph> FromWorld for ComputeGraphPipeline<G> {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let shader = world.load_asset(G::shader_path());
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        let order = topological_order::<G>();
+        let passes = order
+            .iter()
+            .map(|&index| {
+                let pass = &G::passes()[index];
+                let entries: Vec<BindGroupLayoutEntry> = pass
+                    .bindings
+                    .iter()
+                    .map(|binding| BindGroupLayoutEntry {
+                        binding: binding.binding,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: match binding.slot {
+                                Slot::Storage { read_only, .. } => {
+                                    BufferBindingType::Storage { read_only }
+                                }
+                                Slot::Uniform => BufferBindingType::Uniform,
+                            },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    })
+                    .collect();
+                let bind_group_layout = render_device.create_bind_group_layout(G::LABEL, &entries);
+                let pipeline_id =
+                    pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                        label: Some(G::LABEL.into()),
+                        layout: vec![bind_group_layout.clone()],
+                        push_constant_ranges: Vec::new(),
+                        shader: shader.clone(),
+                        shader_defs: vec![],
+                        entry_point: Cow::from(pass.entry_point),
+                    });
+                PassPipeline {
+                    bind_group_layout,
+                    pipeline_id,
+                }
+            })
+            .collect();
+
+        Self {
+            order,
+            passes,
+            _marker: PhantomData,
+        }
+    }
+}
+
+enum ComputeGraphState {
+    Loading,
+    Running,
+}
+
+struct ComputeGraphNode<G> {
+    state: ComputeGraphState,
+    /// Parallel to the pipeline's `order`/`passes`: set once a `run_once` pass has dispatched
+    /// successfully, so `run` skips it on every later frame. Sized and zeroed the moment the
+    /// graph becomes `Running`. `Cell` because `Node::run` only gets `&self`.
+    dispatched_once: Vec<Cell<bool>>,
+    _marker: PhantomData<G>,
+}
+
+impl<G> Default for ComputeGraphNode<G> {
+    fn default() -> Self {
+        Self {
+            state: ComputeGraphState::Loading,
+            dispatched_once: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<G: ComputeGraph> render_graph::Node for ComputeGraphNode<G> {
+    fn update(&mut self, world: &mut World) {
+        if matches!(self.state, ComputeGraphState::Running) {
+            return;
+        }
+
+        let pipeline = world.resource::<ComputeGraphPipeline<G>>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        let mut all_ready = true;
+        for pass in &pipeline.passes {
+            match pipeline_cache.get_compute_pipeline_state(pass.pipeline_id) {
+                CachedPipelineState::Ok(_) => {}
+                CachedPipelineState::Err(err) => {
+                    panic!("Initializing {}: {err}", G::LABEL)
+                }
+                _ => {
+                    // still Queued or Creating - wait for it
+                    all_ready = false;
+                }
+            }
+        }
+
+        if all_ready {
+            self.dispatched_once = pipeline.passes.iter().map(|_| Cell::new(false)).collect();
+            self.state = ComputeGraphState::Running;
+        }
+    }
+
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        if !matches!(self.state, ComputeGraphState::Running) {
+            return Ok(());
+        }
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline = world.resource::<ComputeGraphPipeline<G>>();
+        let Some(bind_groups) = world.get_resource::<ComputeBindGroups<G>>() else {
+            // bind groups not built yet (e.g. the first frame the graph becomes runnable)
+            return Ok(());
+        };
+        let data = world.resource::<G::PreparedData>();
+
+        for (dispatch_index, (&pass_index, pass_pipeline)) in
+            pipeline.order.iter().zip(&pipeline.passes).enumerate()
+        {
+            let pass_descriptor = &G::passes()[pass_index];
+            if pass_descriptor.run_once && self.dispatched_once[dispatch_index].get() {
+                continue;
+            }
+
+            // A still-`Creating` pipeline here (despite `update` having seen every pass settle)
+            // just means the cache was invalidated between frames; skip this pass rather than
+            // panicking and pick it back up next frame.
+            let Some(compute_pipeline) =
+                pipeline_cache.get_compute_pipeline(pass_pipeline.pipeline_id)
+            else {
+                continue;
+            };
+            let workgroups = G::workgroup_count(data, pass_index);
+
+            // One encoder, one scope per pass: later passes in the chain can depend on buffers
+            // this one just wrote.
+            let mut pass = render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor::default());
+            pass.set_bind_group(0, &bind_groups.0[dispatch_index], &[]);
+            pass.set_pipeline(compute_pipeline);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+
+            if pass_descriptor.run_once {
+                self.dispatched_once[dispatch_index].set(true);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+//! The particle simulation's [`ComputeGraph`] implementation.
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource, render_resource::*, renderer::RenderQueue,
+        storage::ShaderStorageBuffer,
+    },
+};
+use bytemuck::{Pod, Zeroable};
+
+use crate::compute_shader::{ComputeGraph, ComputePass, Slot, SlotBinding, SlotId};
+
+pub const SHADER_COMPUTE_PATH: &str = "compute.wgsl";
+pub const WORKGROUP_SIZE: u32 = 32;
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable, ShaderType)]
+pub struct Particle {
+    pub position: Vec3,
+    pub velocity: Vec3,
+}
+
+#[repr(C)]
+#[derive(Default, ShaderType, Clone, Copy, Zeroable, Pod)]
+pub struct ParticleConfig {
+    pub particle_count: u32,
+    /// Seconds since startup, so `update` can integrate on elapsed time rather than per dispatch.
+    pub time: f32,
+    /// Seconds since the last frame, for frame-rate-independent integration.
+    pub delta_time: f32,
+}
+
+/// Data the particle compute pass needs from the main world each frame.
+///
+/// `buffers[0]` and `buffers[1]` ping-pong: each dispatch reads one and writes the other, so a
+/// workgroup can never read a value a neighboring workgroup already overwrote this frame.
+/// `ping` says which is which this frame and is flipped once per frame by [`toggle_ping`], so
+/// the render world (which only ever sees an extracted snapshot) always agrees with the main
+/// world about which buffer is currently safe to read for rendering.
+#[derive(Resource, Clone, ExtractResource)]
+pub struct ParticlePreparedData {
+    pub config_buffer: Buffer,
+    pub buffers: [AssetId<ShaderStorageBuffer>; 2],
+    pub particle_count: u32,
+    pub ping: bool,
+}
+
+impl ParticlePreparedData {
+    /// The buffer most recently finished being written to, i.e. the one to read from for
+    /// rendering.
+    pub fn read_buffer(&self) -> AssetId<ShaderStorageBuffer> {
+        self.buffers[self.ping as usize]
+    }
+}
+
+/// Flips which ping-pong buffer `update` reads from/writes to next frame. Runs in the main
+/// world so the render world's extracted [`ParticlePreparedData`] stays in lockstep with
+/// whatever the compute pass actually just did.
+pub fn toggle_ping(mut data: ResMut<ParticlePreparedData>) {
+    data.ping = !data.ping;
+}
+
+/// Keeps both ping-pong storage buffers alive for as long as the particle entity exists.
+#[derive(Component)]
+pub struct ParticleBufferHandles(pub [Handle<ShaderStorageBuffer>; 2]);
+
+/// Both `init` and `update` read/write the same bound slots (one ping-pong buffer in, the other
+/// out); what differs is `run_once`. `init` seeds the GPU-side state exactly once, then `update`
+/// takes over every frame after.
+const PARTICLE_SLOT_BINDINGS: [SlotBinding; 3] = [
+    SlotBinding {
+        binding: 100,
+        slot: Slot::Storage {
+            name: "particles_in",
+            read_only: true,
+        },
+    },
+    SlotBinding {
+        binding: 101,
+        slot: Slot::Uniform,
+    },
+    SlotBinding {
+        binding: 102,
+        slot: Slot::Storage {
+            name: "particles_out",
+            read_only: false,
+        },
+    },
+];
+
+const PASSES: [ComputePass; 2] = [
+    ComputePass {
+        entry_point: "init",
+        bindings: &PARTICLE_SLOT_BINDINGS,
+        run_once: true,
+    },
+    ComputePass {
+        entry_point: "update",
+        bindings: &PARTICLE_SLOT_BINDINGS,
+        run_once: false,
+    },
+];
+
+/// Marker type wiring the particle simulation into [`ComputeGraphPlugin`](crate::compute_shader::ComputeGraphPlugin).
+pub struct ParticleSim;
+
+impl ComputeGraph for ParticleSim {
+    type PreparedData = ParticlePreparedData;
+
+    const LABEL: &'static str = "particle_compute";
+
+    fn shader_path() -> &'static str {
+        SHADER_COMPUTE_PATH
+    }
+
+    fn passes() -> &'static [ComputePass] {
+        &PASSES
+    }
+
+    fn resolve_storage_slot(
+        data: &ParticlePreparedData,
+        slot: SlotId,
+    ) -> AssetId<ShaderStorageBuffer> {
+        match slot {
+            "particles_in" => data.buffers[data.ping as usize],
+            "particles_out" => data.buffers[1 - data.ping as usize],
+            _ => unreachable!("unknown particle slot {slot}"),
+        }
+    }
+
+    fn uniform_buffer(data: &ParticlePreparedData) -> &Buffer {
+        &data.config_buffer
+    }
+
+    fn workgroup_count(data: &ParticlePreparedData, _pass_index: usize) -> UVec3 {
+        let workgroup_count = (data.particle_count as f32 / WORKGROUP_SIZE as f32).ceil() as u32;
+        UVec3::new(workgroup_count, 1, 1)
+    }
+
+    fn write_uniforms(data: &ParticlePreparedData, queue: &RenderQueue, time: &Time) {
+        let config = ParticleConfig {
+            particle_count: data.particle_count,
+            time: time.elapsed_seconds(),
+            delta_time: time.delta_seconds(),
+        };
+        queue.write_buffer(&data.config_buffer, 0, bytemuck::cast_slice(&[config]));
+    }
+}
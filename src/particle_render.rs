@@ -0,0 +1,271 @@
+//! Instanced rendering for the particle cloud.
+//!
+//! Rather than spawning `particle_count` entities that all share one mesh and material, we
+//! spawn a single entity carrying [`ParticleInstances`] and draw its mesh `count` times in one
+//! draw call. Per-instance position/velocity is read straight out of the same
+//! `ShaderStorageBuffer` the compute shader writes, indexed by `@builtin(instance_index)` in
+//! `render.wgsl` — no CPU round-trip and no per-particle entity.
+
+use std::borrow::Cow;
+
+use bevy::{
+    core_pipeline::core_3d::{Opaque3d, Opaque3dBinKey},
+    ecs::{
+        query::ROQueryItem,
+        system::{lifetimeless::*, SystemParamItem},
+    },
+    pbr::{MeshPipeline, MeshPipelineKey, SetMeshBindGroup, SetMeshViewBindGroup},
+    prelude::*,
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        mesh::GpuBufferInfo,
+        render_asset::RenderAssets,
+        render_phase::{
+            AddRenderCommand, DrawFunctions, RenderCommand, RenderCommandResult, SetItemPipeline,
+            TrackedRenderPass, ViewBinnedRenderPhases,
+        },
+        render_resource::*,
+        renderer::RenderDevice,
+        storage::GpuShaderStorageBuffer,
+        view::ExtractedView,
+        Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::particle::ParticlePreparedData;
+
+/// Marks the single entity whose mesh is drawn `count` times in one instanced draw call.
+#[derive(Component, Clone, Copy, ExtractComponent)]
+pub struct ParticleInstances {
+    pub count: u32,
+}
+
+pub struct ParticleInstancingPlugin;
+
+impl Plugin for ParticleInstancingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(ExtractComponentPlugin::<ParticleInstances>::default());
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .add_render_command::<Opaque3d, DrawParticlesInstanced>()
+            .init_resource::<SpecializedMeshPipelines<ParticleInstancePipeline>>()
+            .add_systems(
+                Render,
+                (
+                    prepare_particle_instance_bind_group.in_set(RenderSet::PrepareBindGroups),
+                    queue_particle_instances.in_set(RenderSet::Queue),
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        app.sub_app_mut(RenderApp)
+            .init_resource::<ParticleInstancePipeline>();
+    }
+}
+
+#[derive(Resource)]
+struct ParticleInstanceBindGroup(BindGroup);
+
+fn prepare_particle_instance_bind_group(
+    mut commands: Commands,
+    pipeline: Res<ParticleInstancePipeline>,
+    data: Option<Res<ParticlePreparedData>>,
+    render_device: Res<RenderDevice>,
+    storage_buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+) {
+    let Some(data) = data else {
+        commands.remove_resource::<ParticleInstanceBindGroup>();
+        return;
+    };
+    // Always read the buffer the compute pass most recently finished writing into, never the
+    // one it's about to write this frame.
+    let Some(storage_buffer) = storage_buffers.get(data.read_buffer()) else {
+        // Drop any bind group built last frame rather than drawing against a stale buffer.
+        commands.remove_resource::<ParticleInstanceBindGroup>();
+        return;
+    };
+
+    let bind_group = render_device.create_bind_group(
+        "Particle Instance Bind Group",
+        &pipeline.particle_bind_group_layout,
+        &[BindGroupEntry {
+            binding: 100,
+            resource: storage_buffer.buffer.as_entire_binding(),
+        }],
+    );
+
+    commands.insert_resource(ParticleInstanceBindGroup(bind_group));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn queue_particle_instances(
+    draw_functions: Res<DrawFunctions<Opaque3d>>,
+    pipeline: Res<ParticleInstancePipeline>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<ParticleInstancePipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<Mesh>>,
+    particles: Query<(Entity, &ParticleInstances, &Handle<Mesh>)>,
+    mut phases: ResMut<ViewBinnedRenderPhases<Opaque3d>>,
+    views: Query<(Entity, &ExtractedView)>,
+) {
+    let draw_function = draw_functions.read().id::<DrawParticlesInstanced>();
+
+    for (view_entity, view) in &views {
+        let Some(phase) = phases.get_mut(&view_entity) else {
+            continue;
+        };
+
+        for (entity, _instances, mesh_handle) in &particles {
+            let Some(mesh) = meshes.get(mesh_handle) else {
+                continue;
+            };
+            let key = MeshPipelineKey::from_msaa_samples(view.msaa_samples)
+                | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology());
+            let Ok(pipeline_id) =
+                pipelines.specialize(&pipeline_cache, &pipeline, key, &mesh.layout)
+            else {
+                continue;
+            };
+
+            phase.add(
+                Opaque3dBinKey {
+                    draw_function,
+                    pipeline: pipeline_id,
+                    asset_id: mesh_handle.id().into(),
+                    material_bind_group_id: None,
+                    lightmap_image: None,
+                },
+                entity,
+                bevy::render::render_phase::BinnedRenderPhaseType::NonMesh,
+            );
+        }
+    }
+}
+
+#[derive(Resource)]
+struct ParticleInstancePipeline {
+    mesh_pipeline: MeshPipeline,
+    particle_bind_group_layout: BindGroupLayout,
+    shader: Handle<Shader>,
+}
+
+impl FromWorld for ParticleInstancePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let particle_bind_group_layout = render_device.create_bind_group_layout(
+            "Particle Instance Bind Group Layout",
+            &[BindGroupLayoutEntry {
+                binding: 100,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        );
+
+        Self {
+            mesh_pipeline: world.resource::<MeshPipeline>().clone(),
+            particle_bind_group_layout,
+            shader: world.load_asset(crate::SHADER_RENDER_PATH),
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for ParticleInstancePipeline {
+    type Key = MeshPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayoutRef,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+        descriptor.vertex.shader = self.shader.clone();
+        descriptor.fragment.as_mut().unwrap().shader = self.shader.clone();
+        descriptor
+            .layout
+            .push(self.particle_bind_group_layout.clone());
+        descriptor.label = Some(Cow::Borrowed("particle_instanced_pipeline"));
+        Ok(descriptor)
+    }
+}
+
+type DrawParticlesInstanced = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    SetParticleInstanceBindGroup<2>,
+    DrawMeshInstanced,
+);
+
+struct SetParticleInstanceBindGroup<const I: usize>;
+
+impl<P: bevy::render::render_phase::PhaseItem, const I: usize> RenderCommand<P>
+    for SetParticleInstanceBindGroup<I>
+{
+    // Optional: `prepare_particle_instance_bind_group` removes this resource on a stale buffer
+    // rather than leaving a bind group from a previous frame in place, so this command must be
+    // able to skip the draw instead of panicking on a missing resource.
+    type Param = Option<SRes<ParticleInstanceBindGroup>>;
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        _entity: Option<()>,
+        bind_group: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(bind_group) = bind_group else {
+            return RenderCommandResult::Skip;
+        };
+        pass.set_bind_group(I, &bind_group.into_inner().0, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+struct DrawMeshInstanced;
+
+impl<P: bevy::render::render_phase::PhaseItem> RenderCommand<P> for DrawMeshInstanced {
+    type Param = SRes<RenderAssets<Mesh>>;
+    type ViewQuery = ();
+    type ItemQuery = (Read<ParticleInstances>, Read<Handle<Mesh>>);
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        instance_data: Option<ROQueryItem<'w, Self::ItemQuery>>,
+        meshes: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some((instances, mesh_handle)) = instance_data else {
+            return RenderCommandResult::Skip;
+        };
+        let Some(mesh) = meshes.into_inner().get(mesh_handle) else {
+            return RenderCommandResult::Skip;
+        };
+
+        pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        match &mesh.buffer_info {
+            GpuBufferInfo::Indexed {
+                buffer,
+                index_format,
+                count,
+            } => {
+                pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                pass.draw_indexed(0..*count, 0, 0..instances.count);
+            }
+            GpuBufferInfo::NonIndexed => {
+                pass.draw(0..mesh.vertex_count, 0..instances.count);
+            }
+        }
+
+        RenderCommandResult::Success
+    }
+}